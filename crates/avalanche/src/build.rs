@@ -1,6 +1,19 @@
+mod git_shallow;
+mod log_stream;
+mod notifier;
+mod pty;
+mod scheduler;
+mod upload;
+
+// `State` owns one `Scheduler` for the process (constructed once from
+// `Config::max_concurrent_builds`) so every `build`/`cancel` call shares the
+// same concurrency limit instead of each call racing to size it.
+pub use scheduler::Scheduler;
+
 use std::path::Path;
+use std::sync::Arc;
 
-use color_eyre::eyre::{Context, OptionExt, Report, Result};
+use color_eyre::eyre::{Context, OptionExt, Report, Result, eyre};
 use http::Uri;
 use itertools::Itertools;
 use service::{Collectable, Remote, collectable, git};
@@ -11,9 +24,17 @@ use service::{
 };
 use sha2::{Digest, Sha256};
 use tokio::fs::{self, File};
-use tracing::{error, info};
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
 
 use crate::Config;
+use log_stream::LogStreamer;
+
+/// Returns `Some("build cancelled")` as an error if `cancel_token` has been
+/// tripped, so callers can short-circuit between build phases.
+fn cancelled(cancel_token: &CancellationToken) -> Option<Report> {
+    cancel_token.is_cancelled().then(|| eyre!("build cancelled"))
+}
 
 #[tracing::instrument(
     skip_all,
@@ -29,11 +50,31 @@ pub async fn build(request: PackageBuild, endpoint: Endpoint, state: State, conf
         service::Client::new(endpoint.host_address.clone()).with_endpoint_auth(endpoint.id, state.service_db.clone());
 
     let task_id = request.build_id;
+    let uri = request.uri.clone();
+    let commit_ref = request.commit_ref.clone();
+    let started_at = std::time::Instant::now();
+
+    let scheduler = state.scheduler.clone();
+    let slot = scheduler.submit(task_id).await;
+    let cancel_token = slot.cancellation_token();
 
-    let status = match run(request, endpoint, state, config).await {
+    let status = match run(request, endpoint, state, config.clone(), client.clone(), cancel_token).await {
         Ok((None, collectables)) => {
             info!("Build succeeded");
 
+            notifier::notify(
+                &config.notifiers,
+                notifier::Completion {
+                    build_id: task_id,
+                    uri: &uri,
+                    commit_ref: &commit_ref,
+                    duration: started_at.elapsed(),
+                    status: notifier::Status::Succeeded,
+                    collectables: &collectables,
+                },
+            )
+            .await;
+
             client
                 .send::<api::v1::summit::BuildSucceeded>(&api::v1::summit::BuildBody { task_id, collectables })
                 .await
@@ -42,6 +83,25 @@ pub async fn build(request: PackageBuild, endpoint: Endpoint, state: State, conf
             let error = error::chain(e.as_ref() as &dyn std::error::Error);
             error!(%error, "Build failed");
 
+            let status = if slot.cancellation_token().is_cancelled() {
+                notifier::Status::Cancelled
+            } else {
+                notifier::Status::Failed
+            };
+
+            notifier::notify(
+                &config.notifiers,
+                notifier::Completion {
+                    build_id: task_id,
+                    uri: &uri,
+                    commit_ref: &commit_ref,
+                    duration: started_at.elapsed(),
+                    status,
+                    collectables: &collectables,
+                },
+            )
+            .await;
+
             client
                 .send::<api::v1::summit::BuildFailed>(&api::v1::summit::BuildBody { task_id, collectables })
                 .await
@@ -50,6 +110,19 @@ pub async fn build(request: PackageBuild, endpoint: Endpoint, state: State, conf
             let error = error::chain(e.as_ref() as &dyn std::error::Error);
             error!(%error, "Build failed");
 
+            notifier::notify(
+                &config.notifiers,
+                notifier::Completion {
+                    build_id: task_id,
+                    uri: &uri,
+                    commit_ref: &commit_ref,
+                    duration: started_at.elapsed(),
+                    status: notifier::Status::Failed,
+                    collectables: &[],
+                },
+            )
+            .await;
+
             client
                 .send::<api::v1::summit::BuildFailed>(&api::v1::summit::BuildBody {
                     task_id,
@@ -63,6 +136,14 @@ pub async fn build(request: PackageBuild, endpoint: Endpoint, state: State, conf
         let error = error::chain(e);
         error!(%error, "Failed to send build status response");
     }
+
+    scheduler.release(task_id, slot).await;
+}
+
+/// Cancels an in-flight build tracked by `state`'s scheduler. Returns
+/// `false` if no build with this id is currently running.
+pub async fn cancel(build_id: u64, state: &State) -> bool {
+    state.scheduler.cancel(build_id).await
 }
 
 async fn run(
@@ -70,6 +151,8 @@ async fn run(
     _endpoint: Endpoint,
     state: State,
     config: Config,
+    client: service::Client,
+    cancel_token: CancellationToken,
 ) -> Result<(Option<Report>, Vec<Collectable>)> {
     let uri = request.uri.parse::<Uri>().context("invalid upstream URI")?;
 
@@ -92,44 +175,141 @@ async fn run(
     let asset_dir = state.root.join("assets").join(request.build_id.to_string());
     recreate_dir(&asset_dir).await.context("recreate asset dir")?;
 
-    let log_file = asset_dir.join("build.log");
+    let shallow = git_shallow::checkout_shallow(&state.cache_dir, &worktree_dir, &uri, &request.commit_ref)
+        .await
+        .unwrap_or_else(|e| {
+            warn!(%uri, commit_ref = request.commit_ref, error = %e, "Shallow checkout failed, falling back to mirror");
+            false
+        });
 
-    if mirror_dir.exists() {
-        info!(%uri, "Updating mirror of recipe repo");
+    if !shallow {
+        if mirror_dir.exists() {
+            info!(%uri, "Updating mirror of recipe repo");
 
-        git::remote_update(&mirror_dir).await?;
-    } else {
-        info!(%uri, "Creating mirror of recipe repo");
+            git::remote_update(&mirror_dir).await?;
+        } else {
+            info!(%uri, "Creating mirror of recipe repo");
+
+            git::mirror(&uri, &mirror_dir).await?;
+        }
 
-        git::mirror(&uri, &mirror_dir).await?;
+        info!(commit_ref = request.commit_ref, "Checking out commit ref to worktree");
+        git::checkout_worktree(&mirror_dir, &worktree_dir, &request.commit_ref)
+            .await
+            .context("checkout commit as worktree")?;
     }
 
-    info!(commit_ref = request.commit_ref, "Checking out commit ref to worktree");
-    git::checkout_worktree(&mirror_dir, &worktree_dir, &request.commit_ref)
-        .await
-        .context("checkout commit as worktree")?;
+    if let Some(report) = cancelled(&cancel_token) {
+        info!("Build cancelled after checkout");
+        cleanup_dirs(&worktree_dir, &asset_dir).await;
+        return Ok((Some(report), vec![]));
+    }
+
+    // `"avalanche"` isn't an architecture, so tagging an ordinary (no
+    // `architectures` requested) build's collectables with it broke
+    // summit's cross-arch assembly, which keys on a real arch name. Default
+    // to the architecture this agent is actually running on instead.
+    let architectures = if request.architectures.is_empty() {
+        vec![std::env::consts::ARCH.to_string()]
+    } else {
+        request.architectures.clone()
+    };
 
-    create_boulder_config(&work_dir, &request.remotes)
+    create_boulder_config(&work_dir, &request.remotes, &architectures)
         .await
         .context("create boulder config")?;
 
-    let error = build_recipe(&work_dir, &asset_dir, &worktree_dir, &request.relative_path, &log_file)
+    let mut collectables = vec![];
+    let mut failures = vec![];
+
+    // One streamer (and one sequence space) for the whole build rather than
+    // one per architecture: summit only ever sees a single "log complete"
+    // marker for `build_id`, and chunks from every architecture order
+    // correctly against each other instead of each arch restarting at
+    // sequence 0.
+    let (log_streamer, log_stream_task) = LogStreamer::spawn(client.clone(), request.build_id);
+
+    for architecture in &architectures {
+        if let Some(report) = cancelled(&cancel_token) {
+            info!(architecture, "Build cancelled before architecture started");
+            failures.push(report);
+            break;
+        }
+
+        let arch_asset_dir = asset_dir.join(architecture);
+        ensure_dir_exists(&arch_asset_dir)
+            .await
+            .context("create per-architecture asset dir")?;
+
+        let log_file = arch_asset_dir.join("build.log");
+
+        // `cancel_token` is already threaded into `build_recipe` (and, through
+        // it, `execute`/`pty::run`), which tears the child process down
+        // itself on cancellation. Racing a second `cancelled()` branch out
+        // here would just abandon that in-flight future instead of waiting
+        // for it, detaching the tee tasks mid-write and handing
+        // `compress_file` below a log file nothing has finished with yet.
+        let error = build_recipe(
+            &work_dir,
+            &arch_asset_dir,
+            &worktree_dir,
+            &request.relative_path,
+            &log_file,
+            &log_streamer,
+            &config,
+            &cancel_token,
+            architecture,
+        )
         .await
         .err();
 
-    tokio::task::spawn_blocking(move || compress_file(&log_file))
-        .await
-        .context("spawn blocking")?
-        .context("compress log file")?;
+        tokio::task::spawn_blocking(move || compress_file(&log_file))
+            .await
+            .context("spawn blocking")?
+            .context("compress log file")?;
 
-    let collectables = scan_collectables(request.build_id, &config.host_address, &asset_dir)
+        if let Some(error) = error {
+            error!(architecture, %error, "Architecture build failed");
+            failures.push(error);
+            continue;
+        }
+
+        let arch_collectables = scan_collectables(
+            &client,
+            &config,
+            request.build_id,
+            request.upload_token.as_deref(),
+            &arch_asset_dir,
+            architecture,
+        )
         .await
         .context("scan collectables")?;
 
-    info!("Removing worktree");
-    git::remove_worktree(&mirror_dir, &worktree_dir)
-        .await
-        .context("remove worktree")?;
+        collectables.extend(arch_collectables);
+    }
+
+    log_streamer.finish().await;
+    log_stream_task.await.context("join log stream task")?;
+
+    // Must run before `cleanup_dirs`: `git::remove_worktree` needs
+    // `worktree_dir` to still exist to unregister it from the mirror, and
+    // running it after the directory has already been deleted would fail
+    // the whole build with `Status::Failed` instead of `Status::Cancelled`.
+    if !shallow {
+        info!("Removing worktree");
+        git::remove_worktree(&mirror_dir, &worktree_dir)
+            .await
+            .context("remove worktree")?;
+    }
+
+    if cancelled(&cancel_token).is_some() {
+        cleanup_dirs(&worktree_dir, &asset_dir).await;
+    }
+
+    let error = (!failures.is_empty()).then(|| {
+        let summary = failures.iter().map(|e| e.to_string()).join("; ");
+        eyre!("{} of {} architecture(s) failed: {summary}", failures.len(), architectures.len())
+    });
 
     Ok((error, collectables))
 }
@@ -146,8 +326,27 @@ async fn recreate_dir(path: &Path) -> Result<()> {
     Ok(fs::create_dir_all(path).await?)
 }
 
-async fn create_boulder_config(work_dir: &Path, remotes: &[Remote]) -> Result<()> {
-    info!("Creating boulder config");
+/// Best-effort teardown of a cancelled build's working directories. Errors
+/// are logged rather than propagated, since the build is already being
+/// abandoned.
+async fn cleanup_dirs(worktree_dir: &Path, asset_dir: &Path) {
+    if let Err(e) = fs::remove_dir_all(worktree_dir).await {
+        warn!(error = %e, "Failed to remove worktree dir after cancellation");
+    }
+
+    if let Err(e) = fs::remove_dir_all(asset_dir).await {
+        warn!(error = %e, "Failed to remove asset dir after cancellation");
+    }
+}
+
+/// Writes one boulder profile per target architecture, each named after
+/// the architecture itself so `build_recipe` can select it with `-p`.
+///
+/// Each profile sets `arch` to its own target so boulder actually
+/// cross-compiles for it rather than building the host's native
+/// architecture under every profile name.
+async fn create_boulder_config(work_dir: &Path, remotes: &[Remote], architectures: &[String]) -> Result<()> {
+    info!(?architectures, "Creating boulder config");
 
     let remotes = remotes
         .iter()
@@ -164,22 +363,25 @@ async fn create_boulder_config(work_dir: &Path, remotes: &[Remote]) -> Result<()
         })
         .join("\n");
 
-    let config = format!(
-        "
-avalanche:
-    repositories:
-{remotes}
-        "
-    );
-
     let config_dir = work_dir.join("etc/boulder/profile.d");
     ensure_dir_exists(&config_dir)
         .await
         .context("create boulder config dir")?;
 
-    fs::write(config_dir.join("avalanche.yaml"), config)
-        .await
-        .context("write boulder config")?;
+    for architecture in architectures {
+        let config = format!(
+            "
+{architecture}:
+    arch: {architecture}
+    repositories:
+{remotes}
+        "
+        );
+
+        fs::write(config_dir.join(format!("{architecture}.yaml")), config)
+            .await
+            .context("write boulder config")?;
+    }
 
     Ok(())
 }
@@ -190,35 +392,174 @@ async fn build_recipe(
     worktree_dir: &Path,
     relative_path: &str,
     log_path: &Path,
+    log_streamer: &Arc<LogStreamer>,
+    config: &Config,
+    cancel_token: &CancellationToken,
+    architecture: &str,
 ) -> Result<()> {
-    let log_file = File::create(log_path)
+    info!(architecture, "Building recipe");
+
+    if config.use_pty {
+        let log_file = File::create(log_path)
+            .await
+            .context("create log file")?
+            .into_std()
+            .await;
+
+        let status = tokio::task::spawn_blocking({
+            let asset_dir = asset_dir.to_owned();
+            let config_dir = work_dir.join("etc/boulder");
+            let worktree_dir = worktree_dir.to_owned();
+            let relative_path = relative_path.to_owned();
+            let architecture = architecture.to_owned();
+            let streamer = log_streamer.clone();
+            let cancel_token = cancel_token.clone();
+            move || {
+                pty::run(
+                    &asset_dir,
+                    &config_dir,
+                    &worktree_dir,
+                    &relative_path,
+                    &architecture,
+                    log_file,
+                    streamer,
+                    cancel_token,
+                )
+            }
+        })
         .await
-        .context("create log file")?
-        .into_std()
-        .await;
+        .context("spawn blocking")?
+        .context("run recipe under pty")?;
+
+        return status
+            .success()
+            .then_some(())
+            .ok_or_eyre("boulder exited with a non-zero status");
+    }
+
+    let (stdout_read, stdout_write) = os_pipe::pipe().context("create stdout pipe")?;
+    let (stderr_read, stderr_write) = os_pipe::pipe().context("create stderr pipe")?;
 
-    info!("Building recipe");
+    let log_file = File::create(log_path).await.context("create log file")?;
+    let stdout_log_file = log_file.try_clone().await.context("clone log file")?.into_std().await;
+    let stderr_log_file = log_file.into_std().await;
 
-    let stdout = log_file.try_clone()?;
-    let stderr = log_file;
+    let stdout_tee = tokio::task::spawn_blocking({
+        let streamer = log_streamer.clone();
+        move || tee_pipe(stdout_read, stdout_log_file, streamer)
+    });
+    let stderr_tee = tokio::task::spawn_blocking({
+        let streamer = log_streamer.clone();
+        move || tee_pipe(stderr_read, stderr_log_file, streamer)
+    });
 
-    service::process::execute("sudo", |process| {
+    let result = service::process::execute("sudo", Some(cancel_token.clone()), |process| {
         process
-            .args(["nice", "-n20", "boulder", "build", "-p", "avalanche", "--update", "-o"])
+            .args(["nice", "-n20", "boulder", "build", "-p", architecture, "--update", "-o"])
             .arg(asset_dir)
             .arg("--config-dir")
             .arg(work_dir.join("etc/boulder"))
             .arg("--")
             .arg(relative_path)
             .current_dir(worktree_dir)
-            .stdout(stdout)
-            .stderr(stderr)
+            .stdout(stdout_write)
+            .stderr(stderr_write)
     })
-    .await?;
+    .await;
+
+    let (stdout_result, stderr_result) = tokio::join!(stdout_tee, stderr_tee);
+    stdout_result.context("spawn blocking")?.context("stream stdout to log")?;
+    stderr_result.context("spawn blocking")?.context("stream stderr to log")?;
+
+    result?;
 
     Ok(())
 }
 
+/// Chunks are flushed to summit after this many buffered lines...
+const LOG_FLUSH_LINES: usize = 100;
+/// ...or by a timer that fires at this fixed interval, whichever comes
+/// first, so a quiet build still shows live progress instead of stalling
+/// until the line threshold fills.
+const LOG_FLUSH_INTERVAL: std::time::Duration = std::time::Duration::from_millis(500);
+
+/// Lines buffered for summit since the last flush.
+#[derive(Default)]
+struct PendingLines {
+    bytes: Vec<u8>,
+    count: usize,
+}
+
+/// Reads a build's output line by line, writing every line to the local log
+/// file as it arrives and batching lines into summit chunks (flushed every
+/// [`LOG_FLUSH_LINES`] lines or [`LOG_FLUSH_INTERVAL`], whichever is first).
+/// Returns once the writing end (held by the boulder child) is closed.
+fn tee_pipe(reader: impl std::io::Read, mut file: std::fs::File, streamer: Arc<LogStreamer>) -> Result<()> {
+    use std::io::{BufRead, BufReader, Write};
+    use std::sync::Mutex;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    let pending = Arc::new(Mutex::new(PendingLines::default()));
+    let done = Arc::new(AtomicBool::new(false));
+
+    // A plain timer thread, not a tokio task: `tee_pipe` runs inside
+    // `spawn_blocking`, so there's no executor here to drive async sleeps.
+    let flusher = std::thread::spawn({
+        let pending = pending.clone();
+        let streamer = streamer.clone();
+        let done = done.clone();
+        move || {
+            while !done.load(Ordering::Relaxed) {
+                std::thread::sleep(LOG_FLUSH_INTERVAL);
+                flush_pending_lines(&pending, &streamer);
+            }
+        }
+    });
+
+    let mut reader = BufReader::new(reader);
+    let mut line = Vec::new();
+
+    loop {
+        line.clear();
+        let read = reader.read_until(b'\n', &mut line).context("read line from pipe")?;
+        if read == 0 {
+            break;
+        }
+
+        file.write_all(&line).context("write line to log file")?;
+
+        let mut pending_lines = pending.lock().expect("pending lines mutex poisoned");
+        pending_lines.bytes.extend_from_slice(&line);
+        pending_lines.count += 1;
+
+        if pending_lines.count >= LOG_FLUSH_LINES {
+            let bytes = std::mem::take(&mut pending_lines.bytes);
+            pending_lines.count = 0;
+            drop(pending_lines);
+            streamer.push(bytes);
+        }
+    }
+
+    done.store(true, Ordering::Relaxed);
+    let _ = flusher.join();
+    flush_pending_lines(&pending, &streamer);
+
+    Ok(())
+}
+
+/// Flushes any buffered lines to summit, if there are any.
+fn flush_pending_lines(pending: &std::sync::Mutex<PendingLines>, streamer: &Arc<LogStreamer>) {
+    let mut pending_lines = pending.lock().expect("pending lines mutex poisoned");
+    if pending_lines.bytes.is_empty() {
+        return;
+    }
+
+    let bytes = std::mem::take(&mut pending_lines.bytes);
+    pending_lines.count = 0;
+    drop(pending_lines);
+    streamer.push(bytes);
+}
+
 fn compress_file(file: &Path) -> Result<()> {
     use flate2::write::GzEncoder;
     use std::fs::{self, File};
@@ -239,7 +580,14 @@ fn compress_file(file: &Path) -> Result<()> {
     Ok(())
 }
 
-async fn scan_collectables(build_id: u64, host_address: &Uri, asset_dir: &Path) -> Result<Vec<Collectable>> {
+async fn scan_collectables(
+    client: &service::Client,
+    config: &Config,
+    build_id: u64,
+    upload_token: Option<&str>,
+    asset_dir: &Path,
+    architecture: &str,
+) -> Result<Vec<Collectable>> {
     let mut collectables = vec![];
 
     let mut contents = fs::read_dir(asset_dir).await.context("read asset dir")?;
@@ -263,16 +611,33 @@ async fn scan_collectables(build_id: u64, host_address: &Uri, asset_dir: &Path)
             kind = collectable::Kind::Package;
         }
 
-        let uri = format!("{host_address}assets/{build_id}/{file_name}")
-            .parse()
-            .context("invalid asset URI")?;
-
-        let sha256sum = tokio::task::spawn_blocking(move || compute_sha256(&path))
-            .await
-            .context("spawn blocking")?
-            .context("compute asset sha256")?;
+        let sha256sum = tokio::task::spawn_blocking({
+            let path = path.clone();
+            move || compute_sha256(&path)
+        })
+        .await
+        .context("spawn blocking")?
+        .context("compute asset sha256")?;
+
+        let uri = match (config.push_artifacts, upload_token) {
+            (true, Some(token)) => {
+                upload::upload(client, token, build_id, &path, kind, &sha256sum)
+                    .await
+                    .context("upload collectable to summit")?;
+
+                format!("summit://objects/{sha256sum}").parse().context("invalid asset URI")?
+            }
+            _ => format!("{}assets/{build_id}/{architecture}/{file_name}", config.host_address)
+                .parse()
+                .context("invalid asset URI")?,
+        };
 
-        collectables.push(Collectable { kind, uri, sha256sum })
+        collectables.push(Collectable {
+            kind,
+            uri,
+            sha256sum,
+            architecture: architecture.to_owned(),
+        })
     }
 
     Ok(collectables)
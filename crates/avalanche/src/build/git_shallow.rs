@@ -0,0 +1,106 @@
+use std::path::Path;
+
+use color_eyre::eyre::{Context, Result};
+use git2::{FetchOptions, Repository, build::CheckoutBuilder};
+use http::Uri;
+use sha2::{Digest, Sha256};
+use tracing::{info, warn};
+
+/// Attempts a shallow, single-commit fetch of `commit_ref` from `uri`,
+/// materializing it directly into `worktree_dir` without cloning the repo's
+/// full history. Reuses an on-disk git2 object store under `cache_dir`
+/// (keyed by repo URI) across builds of the same recipe repo, so only the
+/// new objects for `commit_ref` need to be fetched.
+///
+/// `commit_ref` is typically a raw commit SHA rather than a branch or tag
+/// name, and fetching an arbitrary SHA only succeeds against servers
+/// configured to allow it (e.g. `uploadpack.allowReachableSHA1InWant` /
+/// `allowAnySHA1InWant`) — most public git hosts don't. This path is
+/// therefore opportunistic by design, not a guaranteed fast path: whenever
+/// the upstream doesn't allow it, every build for that repo falls back to
+/// the caller's mirror + worktree checkout, which is always correct, just
+/// slower.
+///
+/// Returns `Ok(false)` when the server doesn't advertise `commit_ref` for a
+/// shallow fetch (or any other reason a shallow fetch isn't possible), so
+/// the caller can fall back to the mirror + worktree checkout path.
+pub async fn checkout_shallow(cache_dir: &Path, worktree_dir: &Path, uri: &Uri, commit_ref: &str) -> Result<bool> {
+    let cache_dir = cache_dir.to_owned();
+    let worktree_dir = worktree_dir.to_owned();
+    let uri = uri.to_string();
+    let commit_ref = commit_ref.to_owned();
+
+    tokio::task::spawn_blocking(move || checkout_shallow_blocking(&cache_dir, &worktree_dir, &uri, &commit_ref))
+        .await
+        .context("spawn blocking")?
+}
+
+fn checkout_shallow_blocking(cache_dir: &Path, worktree_dir: &Path, uri: &str, commit_ref: &str) -> Result<bool> {
+    let object_cache_dir = cache_dir.join("git2-objects").join(cache_key(uri));
+    std::fs::create_dir_all(&object_cache_dir).context("create git2 object cache dir")?;
+
+    let repo = Repository::open_bare(&object_cache_dir)
+        .or_else(|_| Repository::init_bare(&object_cache_dir))
+        .context("open or init git2 object cache")?;
+
+    let mut remote = repo.remote_anonymous(uri).context("create anonymous remote")?;
+
+    let mut fetch_options = FetchOptions::new();
+    fetch_options.depth(1);
+
+    info!(uri, commit_ref, "Attempting shallow fetch via git2");
+
+    if let Err(e) = remote.fetch(&[commit_ref], Some(&mut fetch_options), None) {
+        warn!(
+            uri,
+            commit_ref,
+            error = %e,
+            "Shallow fetch unavailable (commit_ref not advertised/fetchable by this server), falling back to mirror"
+        );
+        return Ok(false);
+    }
+
+    let commit = repo
+        .find_reference("FETCH_HEAD")
+        .and_then(|reference| reference.peel_to_commit())
+        .context("resolve fetched commit")?;
+
+    if worktree_dir.exists() {
+        std::fs::remove_dir_all(worktree_dir).context("clear worktree dir")?;
+    }
+    std::fs::create_dir_all(worktree_dir).context("create worktree dir")?;
+
+    let tree = commit.tree().context("get commit tree")?;
+
+    repo.checkout_tree(tree.as_object(), Some(CheckoutBuilder::new().target_dir(worktree_dir).force()))
+        .context("checkout tree into worktree")?;
+
+    Ok(true)
+}
+
+/// Deterministic, filesystem-safe cache directory name for a repo URI.
+fn cache_key(uri: &str) -> String {
+    hex::encode(Sha256::digest(uri.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::cache_key;
+
+    #[test]
+    fn cache_key_is_deterministic_and_filesystem_safe() {
+        let key = cache_key("https://github.com/aerynos/example.git");
+
+        assert_eq!(key, cache_key("https://github.com/aerynos/example.git"));
+        assert_eq!(key.len(), 64);
+        assert!(key.chars().all(|c| c.is_ascii_hexdigit()));
+    }
+
+    #[test]
+    fn cache_key_differs_between_uris() {
+        let a = cache_key("https://github.com/aerynos/example.git");
+        let b = cache_key("https://github.com/aerynos/other.git");
+
+        assert_ne!(a, b);
+    }
+}
@@ -0,0 +1,192 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use service::{Client, api, error};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tracing::warn;
+
+/// How many in-flight chunks we're willing to buffer for summit before we
+/// start dropping them rather than stalling the build.
+const CHANNEL_CAPACITY: usize = 64;
+
+enum Message {
+    Chunk { sequence: u64, bytes: Vec<u8> },
+    Complete { sequence: u64 },
+}
+
+/// Forwards a build's output to summit as ordered, sequence-numbered
+/// chunks so a live viewer can follow an in-progress build.
+///
+/// The caller is responsible for writing the same bytes straight to the
+/// local log file; this only concerns the best-effort copy sent to
+/// summit. A saturated channel (summit can't keep up) never blocks the
+/// build: the chunk is dropped and the resulting gap in sequence numbers
+/// tells the server it missed something, rather than the build stalling
+/// to guarantee delivery.
+pub struct LogStreamer {
+    task_id: u64,
+    next_sequence: AtomicU64,
+    sender: mpsc::Sender<Message>,
+}
+
+impl LogStreamer {
+    /// Spawns the task that forwards chunks to summit, returning the
+    /// streamer handle and a join handle that resolves once the "log
+    /// complete" marker has been flushed.
+    pub fn spawn(client: Client, task_id: u64) -> (Arc<Self>, JoinHandle<()>) {
+        let (sender, receiver) = mpsc::channel(CHANNEL_CAPACITY);
+
+        let handle = tokio::spawn(forward(client, task_id, receiver));
+
+        (
+            Arc::new(Self {
+                task_id,
+                next_sequence: AtomicU64::new(0),
+                sender,
+            }),
+            handle,
+        )
+    }
+
+    /// Queues a chunk of raw output for best-effort delivery to summit.
+    pub fn push(&self, bytes: Vec<u8>) {
+        let sequence = self.next_sequence.fetch_add(1, Ordering::Relaxed);
+
+        if self.sender.try_send(Message::Chunk { sequence, bytes }).is_err() {
+            warn!(
+                task_id = self.task_id,
+                sequence, "Dropping build log chunk, summit stream is saturated"
+            );
+        }
+    }
+
+    /// Queues the final "log complete" marker, waiting for channel space if
+    /// needed. It doesn't wait for the forwarding task to drain: await the
+    /// join handle from [`LogStreamer::spawn`] for that, before the build
+    /// transitions to its terminal status.
+    pub async fn finish(&self) {
+        let sequence = self.next_sequence.load(Ordering::Relaxed);
+
+        if self.sender.send(Message::Complete { sequence }).await.is_err() {
+            warn!(task_id = self.task_id, "Failed to queue build log completion marker");
+        }
+    }
+}
+
+#[cfg(test)]
+impl LogStreamer {
+    /// Builds a streamer around a bare channel, without spawning [`forward`]
+    /// or requiring a real [`Client`], so `push`/`finish`'s sequencing and
+    /// backpressure behavior can be tested directly.
+    fn for_test(capacity: usize) -> (Arc<Self>, mpsc::Receiver<Message>) {
+        let (sender, receiver) = mpsc::channel(capacity);
+
+        (
+            Arc::new(Self {
+                task_id: 0,
+                next_sequence: AtomicU64::new(0),
+                sender,
+            }),
+            receiver,
+        )
+    }
+}
+
+async fn forward(client: Client, task_id: u64, mut receiver: mpsc::Receiver<Message>) {
+    while let Some(message) = receiver.recv().await {
+        let (sequence, bytes, complete) = match message {
+            Message::Chunk { sequence, bytes } => (sequence, bytes, false),
+            Message::Complete { sequence } => {
+                // This is always the last message, so there's no point
+                // keeping the channel open past it.
+                receiver.close();
+
+                (sequence, vec![], true)
+            }
+        };
+
+        let body = api::v1::summit::BuildLogBody {
+            task_id,
+            sequence,
+            bytes,
+            complete,
+        };
+
+        if let Err(e) = client.send::<api::v1::summit::BuildLog>(&body).await {
+            let error = error::chain(e);
+            warn!(task_id, sequence, %error, "Failed to stream build log chunk to summit");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn push_assigns_increasing_sequence_numbers() {
+        let (streamer, mut receiver) = LogStreamer::for_test(8);
+
+        streamer.push(b"one".to_vec());
+        streamer.push(b"two".to_vec());
+        streamer.push(b"three".to_vec());
+
+        for (expected_sequence, expected_bytes) in [(0, "one"), (1, "two"), (2, "three")] {
+            match receiver.recv().await.expect("chunk message") {
+                Message::Chunk { sequence, bytes } => {
+                    assert_eq!(sequence, expected_sequence);
+                    assert_eq!(bytes, expected_bytes.as_bytes());
+                }
+                Message::Complete { .. } => panic!("expected a chunk message"),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn push_drops_chunks_when_the_channel_is_saturated() {
+        let (streamer, mut receiver) = LogStreamer::for_test(1);
+
+        // Fills the channel's only slot.
+        streamer.push(b"kept".to_vec());
+        // The channel is full, so this is dropped rather than blocking the build.
+        streamer.push(b"dropped".to_vec());
+
+        match receiver.recv().await.expect("chunk message") {
+            Message::Chunk { sequence, bytes } => {
+                assert_eq!(sequence, 0);
+                assert_eq!(bytes, b"kept");
+            }
+            Message::Complete { .. } => panic!("expected a chunk message"),
+        }
+
+        // A slot is free again, so this one goes through - leaving a gap at
+        // sequence 1 for the server to notice.
+        streamer.push(b"after drop".to_vec());
+
+        match receiver.recv().await.expect("chunk message") {
+            Message::Chunk { sequence, bytes } => {
+                assert_eq!(sequence, 2);
+                assert_eq!(bytes, b"after drop");
+            }
+            Message::Complete { .. } => panic!("expected a chunk message"),
+        }
+    }
+
+    #[tokio::test]
+    async fn finish_sends_the_current_sequence_as_the_complete_marker() {
+        let (streamer, mut receiver) = LogStreamer::for_test(8);
+
+        streamer.push(b"one".to_vec());
+        streamer.push(b"two".to_vec());
+        streamer.finish().await;
+
+        let _ = receiver.recv().await.expect("first chunk");
+        let _ = receiver.recv().await.expect("second chunk");
+
+        match receiver.recv().await.expect("complete message") {
+            Message::Complete { sequence } => assert_eq!(sequence, 2),
+            Message::Chunk { .. } => panic!("expected the complete message"),
+        }
+    }
+}
@@ -0,0 +1,106 @@
+use std::time::Duration;
+
+use serde::Serialize;
+use service::Collectable;
+use tracing::warn;
+
+/// An outbound destination for build completion notifications. New sinks
+/// (chat, email, ...) can be added as variants here without touching the
+/// build pipeline that calls [`notify`].
+#[derive(Debug, Clone)]
+pub enum Sink {
+    /// POSTs a JSON payload describing the completion to `url`.
+    Webhook { url: String },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    Succeeded,
+    Failed,
+    Cancelled,
+}
+
+pub struct Completion<'a> {
+    pub build_id: u64,
+    pub uri: &'a str,
+    pub commit_ref: &'a str,
+    pub duration: Duration,
+    pub status: Status,
+    pub collectables: &'a [Collectable],
+}
+
+#[derive(Serialize)]
+struct Payload<'a> {
+    build_id: u64,
+    uri: &'a str,
+    commit_ref: &'a str,
+    duration_secs: f64,
+    status: Status,
+    collectables: &'a [Collectable],
+}
+
+/// Fires every configured sink for a finished build. A sink failure is
+/// logged and otherwise ignored: a flaky notifier must never fail an
+/// otherwise good build.
+pub async fn notify(sinks: &[Sink], completion: Completion<'_>) {
+    if sinks.is_empty() {
+        return;
+    }
+
+    let payload = Payload {
+        build_id: completion.build_id,
+        uri: completion.uri,
+        commit_ref: completion.commit_ref,
+        duration_secs: completion.duration.as_secs_f64(),
+        status: completion.status,
+        collectables: completion.collectables,
+    };
+
+    for sink in sinks {
+        match sink {
+            Sink::Webhook { url } => notify_webhook(url, &payload).await,
+        }
+    }
+}
+
+async fn notify_webhook(url: &str, payload: &Payload<'_>) {
+    let client = reqwest::Client::new();
+
+    let result = client
+        .post(url)
+        .json(payload)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status());
+
+    if let Err(e) = result {
+        warn!(url, error = %e, "Build completion webhook failed");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn payload_serializes_status_as_snake_case_and_duration_as_seconds() {
+        let payload = Payload {
+            build_id: 42,
+            uri: "https://github.com/aerynos/example.git",
+            commit_ref: "main",
+            duration_secs: Duration::from_millis(1_500).as_secs_f64(),
+            status: Status::Cancelled,
+            collectables: &[],
+        };
+
+        let value = serde_json::to_value(&payload).expect("serialize payload");
+
+        assert_eq!(value["build_id"], 42);
+        assert_eq!(value["uri"], "https://github.com/aerynos/example.git");
+        assert_eq!(value["commit_ref"], "main");
+        assert_eq!(value["duration_secs"], 1.5);
+        assert_eq!(value["status"], "cancelled");
+        assert_eq!(value["collectables"], serde_json::json!([]));
+    }
+}
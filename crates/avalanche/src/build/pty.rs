@@ -0,0 +1,137 @@
+use std::fs::File;
+use std::os::unix::process::CommandExt;
+use std::path::Path;
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::Arc;
+
+use color_eyre::eyre::{Context, Result};
+use nix::pty::{Winsize, openpty};
+use tokio_util::sync::CancellationToken;
+
+use super::log_stream::LogStreamer;
+use super::tee_pipe;
+
+/// Initial PTY window size. Boulder doesn't draw an interactive TUI itself,
+/// but progress output from it (or tools it shells out to) uses this to
+/// decide whether, and how wide, to wrap.
+const INITIAL_WINDOW: Winsize = Winsize {
+    ws_row: 50,
+    ws_col: 200,
+    ws_xpixel: 0,
+    ws_ypixel: 0,
+};
+
+/// Runs `boulder build` attached to a pseudo-terminal instead of plain
+/// pipes, so it (and anything it shells out to) detects a real TTY and
+/// keeps its coloured/interactive progress output instead of falling back
+/// to plain, line-buffered logging.
+///
+/// The master side is tee'd to `log_file` and `log_streamer` exactly like
+/// the piped execution path. The master is always read to EOF and the
+/// slave fds are always closed before the child is reaped, so the process
+/// exits cleanly even when the build itself fails.
+///
+/// If `cancel_token` is cancelled while the child is running, its whole
+/// process group is sent `SIGTERM` via a second, privileged `sudo kill` so
+/// the build tears down promptly instead of running to completion. A plain
+/// signal from this (unprivileged) process would bounce off `sudo`, which
+/// re-execs as root: only root can signal it, and `sudo` alone isn't
+/// guaranteed to forward the signal on to boulder underneath it.
+pub fn run(
+    asset_dir: &Path,
+    config_dir: &Path,
+    worktree_dir: &Path,
+    relative_path: &str,
+    architecture: &str,
+    log_file: File,
+    log_streamer: Arc<LogStreamer>,
+    cancel_token: CancellationToken,
+) -> Result<ExitStatus> {
+    let pty = openpty(Some(&INITIAL_WINDOW), None).context("allocate pty")?;
+
+    let mut command = Command::new("sudo");
+    command
+        .args(["nice", "-n20", "boulder", "build", "-p", architecture, "--update", "-o"])
+        .arg(asset_dir)
+        .arg("--config-dir")
+        .arg(config_dir)
+        .arg("--")
+        .arg(relative_path)
+        .current_dir(worktree_dir)
+        .stdin(Stdio::from(pty.slave.try_clone().context("clone pty slave")?))
+        .stdout(Stdio::from(pty.slave.try_clone().context("clone pty slave")?))
+        .stderr(Stdio::from(pty.slave))
+        // Puts `sudo` (and boulder underneath it, which inherits its
+        // parent's process group) in its own group so cancellation can
+        // signal the whole tree via `killpg` instead of just the direct
+        // child.
+        .process_group(0);
+
+    let mut child = command.spawn().context("spawn child under pty")?;
+
+    // The child holds its own copies of the slave fd from the exec; drop
+    // ours (held inside `command`) so the master reliably sees EOF once
+    // the child's copies are closed, rather than blocking forever.
+    drop(command);
+
+    // Woken either by real cancellation (propagated from `cancel_token`) or
+    // explicitly below once the build finishes on its own, so the watcher
+    // thread never outlives this function.
+    let watcher_token = cancel_token.child_token();
+    // `std::process::Child::id` (unlike tokio's) returns a plain `u32`, not
+    // an `Option`: a spawned child always has a pid until it's reaped. Since
+    // it was spawned with `process_group(0)`, it's also its own pgid.
+    let pid = child.id() as i32;
+    // `std::thread::spawn` gives us a thread with no Tokio context of its
+    // own, so `Handle::current()` must be grabbed here, on the runtime
+    // thread, and carried in rather than looked up inside the closure.
+    let handle = tokio::runtime::Handle::current();
+    let watcher = std::thread::spawn({
+        let watcher_token = watcher_token.clone();
+        let cancel_token = cancel_token.clone();
+        move || {
+            handle.block_on(watcher_token.cancelled());
+
+            if cancel_token.is_cancelled() {
+                kill_process_group(pid);
+            }
+        }
+    });
+
+    let master = PtyMaster(File::from(pty.master));
+
+    let tee_result = tee_pipe(master, log_file, log_streamer).context("stream pty output to log");
+
+    let status = child.wait().context("wait for child");
+
+    watcher_token.cancel();
+    let _ = watcher.join();
+
+    tee_result?;
+    status
+}
+
+/// The PTY master. Linux reports the slave side being closed as `EIO` on
+/// the master rather than as a 0-byte read; this maps it to EOF.
+struct PtyMaster(File);
+
+impl std::io::Read for PtyMaster {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match std::io::Read::read(&mut self.0, buf) {
+            Err(e) if e.raw_os_error() == Some(nix::libc::EIO) => Ok(0),
+            result => result,
+        }
+    }
+}
+
+/// Terminates `pgid`'s whole process group with `SIGTERM`.
+///
+/// `pgid` (and everything in it, including boulder) is owned by root once
+/// `sudo` re-execs, so this process can't `kill(2)` it directly — it isn't
+/// root and isn't the owner. Shelling back out through `sudo` (already
+/// trusted to run boulder as root) gets the signal there instead. Best
+/// effort: if the group is already gone, or `sudo` itself can't run, there's
+/// nothing left to tear down.
+fn kill_process_group(pgid: i32) {
+    let _ = Command::new("sudo").args(["kill", "-TERM", &format!("-{pgid}")]).status();
+}
@@ -0,0 +1,79 @@
+use std::sync::Arc;
+
+use service::sync::SharedMap;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio_util::sync::CancellationToken;
+
+/// Bounds how many builds run concurrently and tracks the in-flight ones so
+/// they can be cancelled by id.
+///
+/// Cloning shares the same underlying semaphore and tracking map, so a
+/// single [`Scheduler`] should be constructed once (sized from
+/// `max_concurrent_builds`) and cloned into whatever holds the service's
+/// shared state, rather than re-derived on every build.
+#[derive(Clone)]
+pub struct Scheduler {
+    semaphore: Arc<Semaphore>,
+    cancellations: SharedMap<u64, CancellationToken>,
+}
+
+impl Scheduler {
+    pub fn new(max_concurrent_builds: usize) -> Self {
+        Self {
+            semaphore: Arc::new(Semaphore::new(max_concurrent_builds)),
+            cancellations: SharedMap::default(),
+        }
+    }
+
+    /// Waits for a free concurrency slot (queueing if `max_concurrent_builds`
+    /// are already running) and registers `build_id`'s cancellation token so
+    /// [`Scheduler::cancel`] can later stop it.
+    pub async fn submit(&self, build_id: u64) -> Slot {
+        let permit = self
+            .semaphore
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("scheduler semaphore is never closed");
+
+        let cancel = CancellationToken::new();
+        self.cancellations.insert(build_id, cancel.clone()).await;
+
+        Slot {
+            cancel,
+            _permit: permit,
+        }
+    }
+
+    /// Removes `build_id`'s tracking entry. The concurrency permit held by
+    /// `slot` is released as it's dropped.
+    pub async fn release(&self, build_id: u64, slot: Slot) {
+        self.cancellations.remove(&build_id).await;
+        drop(slot);
+    }
+
+    /// Requests cancellation of an in-flight build. Returns `false` if no
+    /// build with this id is currently tracked.
+    pub async fn cancel(&self, build_id: u64) -> bool {
+        let Some(token) = self.cancellations.get(&build_id).await else {
+            return false;
+        };
+
+        token.cancel();
+        true
+    }
+}
+
+/// A build's slot in the scheduler. Holds the concurrency permit and the
+/// build's cancellation token; pass it to [`Scheduler::release`] once the
+/// build finishes.
+pub struct Slot {
+    cancel: CancellationToken,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl Slot {
+    pub fn cancellation_token(&self) -> CancellationToken {
+        self.cancel.clone()
+    }
+}
@@ -0,0 +1,103 @@
+use std::path::Path;
+
+use color_eyre::eyre::{Context, Result};
+use service::{Client, api, collectable};
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, SeekFrom};
+use tracing::info;
+
+/// Chunk size for streaming a collectable to summit's upload endpoint.
+const CHUNK_SIZE: usize = 4 * 1024 * 1024;
+
+/// Streams a finished collectable to summit's upload endpoint instead of
+/// the agent serving it from its own asset directory. Uploads are chunked
+/// and keyed by the file's sha256, so summit can verify integrity on
+/// receipt and a retried upload after a transient failure resumes from
+/// the offset it already has rather than restarting from scratch.
+pub async fn upload(
+    client: &Client,
+    token: &str,
+    build_id: u64,
+    path: &Path,
+    kind: collectable::Kind,
+    sha256sum: &str,
+) -> Result<()> {
+    let mut file = File::open(path).await.context("open collectable")?;
+    let total_len = file.metadata().await.context("stat collectable")?.len();
+
+    let mut offset = resume_offset(client, token, sha256sum).await.unwrap_or(0);
+
+    if is_already_uploaded(offset, total_len) {
+        info!(sha256sum, "Collectable already fully uploaded, skipping");
+        return Ok(());
+    }
+
+    file.seek(SeekFrom::Start(offset)).await.context("seek to resume offset")?;
+
+    let mut buf = vec![0u8; CHUNK_SIZE];
+
+    while offset < total_len {
+        let read = file.read(&mut buf).await.context("read collectable chunk")?;
+        if read == 0 {
+            break;
+        }
+
+        client
+            .send::<api::v1::summit::UploadChunk>(&api::v1::summit::UploadChunkBody {
+                token: token.to_owned(),
+                build_id,
+                kind,
+                sha256sum: sha256sum.to_owned(),
+                offset,
+                bytes: buf[..read].to_vec(),
+            })
+            .await
+            .context("send collectable chunk")?;
+
+        offset += read as u64;
+    }
+
+    Ok(())
+}
+
+/// Whether summit already reports having the full file, so the upload can
+/// be skipped entirely rather than resumed.
+fn is_already_uploaded(offset: u64, total_len: u64) -> bool {
+    offset >= total_len
+}
+
+/// Asks summit how much of this sha256-keyed upload it already has, so a
+/// retry can resume instead of restarting. Treated as "nothing uploaded
+/// yet" if the query itself fails.
+async fn resume_offset(client: &Client, token: &str, sha256sum: &str) -> Result<u64> {
+    let response = client
+        .send::<api::v1::summit::UploadStatus>(&api::v1::summit::UploadStatusBody {
+            token: token.to_owned(),
+            sha256sum: sha256sum.to_owned(),
+        })
+        .await
+        .context("query upload status")?;
+
+    Ok(response.offset)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::is_already_uploaded;
+
+    #[test]
+    fn resumes_when_summit_has_a_partial_upload() {
+        assert!(!is_already_uploaded(512, 1024));
+    }
+
+    #[test]
+    fn skips_when_summit_already_has_the_whole_file() {
+        assert!(is_already_uploaded(1024, 1024));
+        assert!(is_already_uploaded(2048, 1024));
+    }
+
+    #[test]
+    fn resumes_from_scratch_when_summit_has_nothing() {
+        assert!(!is_already_uploaded(0, 1024));
+    }
+}
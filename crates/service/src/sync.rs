@@ -28,4 +28,19 @@ where
     pub async fn remove(&self, key: &K) -> Option<V> {
         self.0.lock().await.remove(key)
     }
+
+    /// Returns a clone of the value at `key`, if present.
+    pub async fn get(&self, key: &K) -> Option<V> {
+        self.0.lock().await.get(key).cloned()
+    }
+
+    /// Returns whether `key` is present in the map.
+    pub async fn contains_key(&self, key: &K) -> bool {
+        self.0.lock().await.contains_key(key)
+    }
+
+    /// Returns a snapshot of the keys currently in the map.
+    pub async fn keys(&self) -> Vec<K> {
+        self.0.lock().await.keys().cloned().collect()
+    }
 }